@@ -0,0 +1,69 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// Errors returned by `TimerFD`, `Timer` and `TimerGSource`
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed, e.g. with `EMFILE` or `ENOMEM`, or with `EPERM`
+    /// when creating an alarm timer without `CAP_WAKE_ALARM`
+    Io(io::Error),
+    /// The operation is not allowed on an active timer
+    Active,
+    /// The operation is only allowed on an active timer
+    NotActive,
+    /// The operation is only allowed on a paused timer
+    NotPaused,
+    /// The given time is negative, zero where that is not allowed, or has
+    /// nanoseconds out of range
+    InvalidTime,
+    /// The clock was changed discontinuously, see `TimerEvent::ClockSet`
+    ClockSet,
+    /// The operation is not supported for the clock of the timer
+    UnsupportedClock,
+    /// The operation is not supported by the running kernel
+    UnsupportedByKernel,
+    /// The `TimerGSource` is attached to a context already
+    AlreadyAttached,
+    /// The `TimerGSource` is not attached to a context
+    NotAttached,
+    /// The context can't be used for a `TimerGSource` created with
+    /// `from_local_fn` on this thread
+    ForeignContext,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Active => write!(f, "timer is active"),
+            Error::NotActive => write!(f, "timer is not active"),
+            Error::NotPaused => write!(f, "timer is not paused"),
+            Error::InvalidTime => write!(f, "invalid time"),
+            Error::ClockSet => write!(f, "clock was set"),
+            Error::UnsupportedClock => write!(f, "operation not supported for this clock"),
+            Error::UnsupportedByKernel => write!(f, "operation not supported by the kernel"),
+            Error::AlreadyAttached => write!(f, "source is attached already"),
+            Error::NotAttached => write!(f, "source is not attached"),
+            Error::ForeignContext => write!(f, "context belongs to another thread"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,678 @@
+use std::any::Any;
+use std::cmp;
+use std::convert::TryFrom;
+use std::default;
+use std::marker;
+use std::mem;
+use std::ops;
+use std::os::unix::io::AsRawFd;
+use std::panic;
+use std::process;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::Duration;
+
+use glib;
+use glib::ffi;
+use glib::thread_guard::ThreadGuard;
+use glib::translate::{from_glib_full, IntoGlib, ToGlibPtr};
+
+use error::{Error, Result};
+use time::itimerspec;
+use timer::{MissedTickPolicy, TickInfo, Timer};
+use timerfd::ClockId;
+
+/// What to do after a `TimerGSourceCallback` returned. If the timer can't be
+/// re-armed as requested, e.g. because the duration is too large, the error
+/// is logged as GLib critical and the source is destroyed.
+#[derive(Eq,PartialEq,Clone,Debug)]
+pub enum TimerAction {
+    /// Keep the source and leave the timer as it is
+    Continue,
+    /// Re-arm the timer with this setting, relative to now, see
+    /// `Timer::reschedule`
+    Reschedule(itimerspec),
+    /// Re-arm the timer to expire every given duration, starting one
+    /// period from now
+    SetInterval(Duration),
+    /// Re-arm the timer to expire once, after the given duration
+    Oneshot(Duration),
+    /// Pause the timer, see `Timer::pause`, but keep the source
+    Pause,
+    /// Destroy the source
+    Remove,
+}
+
+impl From<glib::ControlFlow> for TimerAction {
+    fn from(flow: glib::ControlFlow) -> TimerAction {
+        match flow {
+            glib::ControlFlow::Continue => TimerAction::Continue,
+            glib::ControlFlow::Break => TimerAction::Remove,
+        }
+    }
+}
+
+/// Called by a `TimerGSource` when its timer expired.
+///
+/// The expirations are read from the timer before the callback runs, so it
+/// can change the timer freely. Re-arming it, e.g. with `restart`, `stop`
+/// followed by `start`, `reschedule` or a `TimerAction`, drops the
+/// expirations of the old schedule that were not dispatched yet, like the
+/// kernel does. Those of the new schedule are dispatched with the next
+/// iteration of the main loop, none are lost or reported twice.
+pub trait TimerGSourceCallback: Send {
+    /// `tick` describes the expirations since the last dispatch
+    fn callback(&mut self, timer: &mut Timer, tick: &TickInfo) -> TimerAction;
+
+    /// Called instead of `callback` when the clock of a timer with
+    /// `set_cancel_on_set` was changed discontinuously.
+    fn clock_set(&mut self, _timer: &mut Timer) -> TimerAction {
+        TimerAction::Continue
+    }
+}
+
+struct FnCallback<F> {
+    f: F,
+}
+
+impl<F: FnMut(&mut Timer) -> TimerAction + Send> TimerGSourceCallback for FnCallback<F> {
+    fn callback(&mut self, timer: &mut Timer, _tick: &TickInfo) -> TimerAction {
+        (self.f)(timer)
+    }
+}
+
+// Lets a closure that is not `Send` be used as callback, as long as the
+// source is only dispatched on the thread that created it, which `attach`
+// makes sure of. Using or dropping it on another thread panics.
+struct LocalFnCallback<F> {
+    f: ThreadGuard<F>,
+}
+
+impl<F: FnMut(&mut Timer) -> TimerAction> TimerGSourceCallback for LocalFnCallback<F> {
+    fn callback(&mut self, timer: &mut Timer, _tick: &TickInfo) -> TimerAction {
+        (self.f.get_mut())(timer)
+    }
+}
+
+/// Priority of a `TimerGSource` in its main loop, sources with a lower value
+/// are dispatched first
+#[derive(Eq,PartialEq,Ord,PartialOrd,Clone,Debug)]
+pub struct Priority(pub i32);
+
+impl Copy for Priority {}
+
+impl Priority {
+    pub const HIGH: Priority = Priority(ffi::G_PRIORITY_HIGH);
+    pub const DEFAULT: Priority = Priority(ffi::G_PRIORITY_DEFAULT);
+    pub const HIGH_IDLE: Priority = Priority(ffi::G_PRIORITY_HIGH_IDLE);
+    /// GTK resizes at this priority, `HIGH_IDLE + 10`
+    pub const RESIZE: Priority = Priority(ffi::G_PRIORITY_HIGH_IDLE + 10);
+    /// GTK redraws at this priority, `HIGH_IDLE + 20`
+    pub const REDRAW: Priority = Priority(ffi::G_PRIORITY_HIGH_IDLE + 20);
+    pub const DEFAULT_IDLE: Priority = Priority(ffi::G_PRIORITY_DEFAULT_IDLE);
+    pub const LOW: Priority = Priority(ffi::G_PRIORITY_LOW);
+}
+
+impl default::Default for Priority {
+    fn default() -> Priority {
+        Priority::DEFAULT
+    }
+}
+
+impl From<glib::Priority> for Priority {
+    fn from(priority: glib::Priority) -> Priority {
+        Priority(priority.into_glib())
+    }
+}
+
+impl From<Priority> for glib::Priority {
+    fn from(priority: Priority) -> glib::Priority {
+        glib::Priority::from(priority.0)
+    }
+}
+
+/// What happens when the callback of a `TimerGSource` panics. Unwinding
+/// into GLib is not allowed, so the panic is always caught first. This
+/// only covers the callback: if reading the timerfd fails, the error is
+/// logged as GLib critical and the source destroyed.
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+pub enum PanicPolicy {
+    /// Abort the process. This is the default.
+    #[default]
+    Abort,
+    /// Log the panic as GLib critical and destroy the source
+    LogAndDestroy,
+    /// Destroy the source and keep the panic, so it can be re-raised with
+    /// `TimerGSource::resume_panic` once GLib returned
+    Propagate,
+}
+
+impl Copy for PanicPolicy {}
+
+/// How a `TimerGSource` watches its timerfd, see `TimerGSource::set_fd_watch`
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+pub enum FdWatch {
+    /// With `g_source_add_unix_fd`, GLib polls the fd and dispatches the
+    /// source when it is readable. This is the default.
+    #[default]
+    UnixFd,
+    /// With `g_source_add_poll` and a `GPollFD`, whose `revents` the source
+    /// checks itself, like sources had to before GLib 2.36. This doesn't
+    /// make the crate usable with such an old GLib, the glib crate needs at
+    /// least 2.56.
+    PollFd,
+}
+
+impl Copy for FdWatch {}
+
+struct TimerGSourceInner {
+    timer: Timer,
+    callback_object: Box<dyn TimerGSourceCallback+Send>,
+    panic_policy: PanicPolicy,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+// The GSource as allocated by `g_source_new`, with a reference to the state
+// shared with the `TimerGSource`. That reference is only released in
+// `finalize`, so the state outlives every dispatch, no matter whether the
+// handle or GLib destroys the source first.
+#[repr(C)]
+struct TimerGSourceRaw {
+    g_source: ffi::GSource,
+    inner: *const Mutex<TimerGSourceInner>,
+    // Polled by GLib with `FdWatch::PollFd`, so it has to live as long as
+    // the GSource
+    poll_fd: ffi::GPollFD,
+}
+
+pub struct TimerGSource {
+    // A destroyed GSource can't be attached again, so every `attach` creates
+    // a new one
+    source: Option<glib::Source>,
+    // The context may be iterated on another thread, which locks the state
+    // for every dispatch
+    inner: Arc<Mutex<TimerGSourceInner>>,
+    // Applied to every new GSource
+    priority: Priority,
+    name: Option<String>,
+    can_recurse: bool,
+    fd_watch: FdWatch,
+    // Created with `from_local_fn`, its closure has to be called and
+    // dropped on this thread
+    local: bool,
+    _not_send: marker::PhantomData<*const ()>,
+}
+
+/// Gives access to the timer of a `TimerGSource`, see `TimerGSource::timer`
+pub struct TimerGuard<'a> {
+    guard: MutexGuard<'a, TimerGSourceInner>,
+}
+
+impl<'a> ops::Deref for TimerGuard<'a> {
+    type Target = Timer;
+
+    fn deref(&self) -> &Timer {
+        &self.guard.timer
+    }
+}
+
+impl<'a> ops::DerefMut for TimerGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Timer {
+        &mut self.guard.timer
+    }
+}
+
+impl TimerGSource {
+    /// Equivalent to `new_with_clock(ClockId::Monotonic, callback_object)`
+    pub fn new(callback_object: Box<dyn TimerGSourceCallback+Send>) -> Result<TimerGSource> {
+        TimerGSource::new_with_clock(ClockId::Monotonic, callback_object)
+    }
+
+    pub fn new_with_clock(clock: ClockId,
+                          callback_object: Box<dyn TimerGSourceCallback+Send>)
+                          -> Result<TimerGSource> {
+        let tgsi = Arc::new(Mutex::new(TimerGSourceInner {
+            timer: Timer::new_with_clock(clock)?,
+            callback_object: callback_object,
+            panic_policy: PanicPolicy::Abort,
+            panic: None,
+        }));
+        Ok(TimerGSource {
+            source: None,
+            inner: tgsi,
+            priority: Priority::DEFAULT,
+            name: None,
+            can_recurse: false,
+            fd_watch: FdWatch::UnixFd,
+            local: false,
+            _not_send: marker::PhantomData,
+        })
+    }
+
+    /// Equivalent to `from_fn_with_clock(ClockId::Monotonic, f)`
+    pub fn from_fn<F, R>(f: F) -> Result<TimerGSource>
+            where F: FnMut(&mut Timer) -> R + Send + 'static, R: Into<TimerAction> {
+        TimerGSource::from_fn_with_clock(ClockId::Monotonic, f)
+    }
+
+    /// Like `new_with_clock`, with a closure as callback. It can return a
+    /// `TimerAction` or a `glib::ControlFlow`.
+    pub fn from_fn_with_clock<F, R>(clock: ClockId, mut f: F) -> Result<TimerGSource>
+            where F: FnMut(&mut Timer) -> R + Send + 'static, R: Into<TimerAction> {
+        let callback_object = FnCallback { f: move |timer: &mut Timer| f(timer).into() };
+        TimerGSource::new_with_clock(clock, Box::new(callback_object))
+    }
+
+    /// Equivalent to `from_local_fn_with_clock(ClockId::Monotonic, f)`
+    pub fn from_local_fn<F, R>(f: F) -> Result<TimerGSource>
+            where F: FnMut(&mut Timer) -> R + 'static, R: Into<TimerAction> {
+        TimerGSource::from_local_fn_with_clock(ClockId::Monotonic, f)
+    }
+
+    /// Like `from_fn_with_clock`, but `f` doesn't have to be `Send`. The
+    /// source can only be attached to a context that the current thread
+    /// owns, e.g. the global default context on the GTK main thread after
+    /// `gtk::init` or one acquired with `glib::MainContext::acquire`, or
+    /// that it pushed as thread-default context. `attach` fails with
+    /// `Error::ForeignContext` otherwise.
+    pub fn from_local_fn_with_clock<F, R>(clock: ClockId, mut f: F) -> Result<TimerGSource>
+            where F: FnMut(&mut Timer) -> R + 'static, R: Into<TimerAction> {
+        let callback_object = LocalFnCallback {
+            f: ThreadGuard::new(move |timer: &mut Timer| f(timer).into()),
+        };
+        let mut source = TimerGSource::new_with_clock(clock, Box::new(callback_object))?;
+        source.local = true;
+        Ok(source)
+    }
+
+    /// Attaches to `context`, or to the global default context if `None`.
+    /// Fails with `Error::AlreadyAttached` if the source is attached already.
+    pub fn attach(&mut self, context: Option<&glib::MainContext>) -> Result<glib::SourceId> {
+        if self.is_attached() {
+            return Err(Error::AlreadyAttached);
+        }
+        if self.local {
+            // `None` is the global default context. It is not the
+            // thread-default context unless it was pushed explicitly, even
+            // if `ref_thread_default` falls back to it.
+            let context = context.cloned().unwrap_or_else(glib::MainContext::default);
+            let pushed = glib::MainContext::thread_default();
+            if !context.is_owner() && pushed.as_ref() != Some(&context) {
+                return Err(Error::ForeignContext);
+            }
+        }
+        let funcs = match self.fd_watch {
+            FdWatch::UnixFd => ptr::addr_of_mut!(TIMER_GSOURCE_FUNCS),
+            FdWatch::PollFd => ptr::addr_of_mut!(TIMER_GSOURCE_POLL_FUNCS),
+        };
+        let source: glib::Source = unsafe {
+            let raw = ffi::g_source_new(funcs, mem::size_of::<TimerGSourceRaw>() as u32);
+            (*(raw as *mut TimerGSourceRaw)).inner = Arc::into_raw(self.inner.clone());
+            from_glib_full(raw)
+        };
+        let g_source = source.to_glib_none().0;
+        unsafe {
+            ffi::g_source_set_callback(
+                g_source,
+                Some(dispatch_timerfd_g_source_for_realz),
+                Arc::as_ptr(&self.inner) as ffi::gpointer,
+                None);
+            let fd = self.lock().timer.as_raw_fd();
+            match self.fd_watch {
+                FdWatch::UnixFd => {
+                    let _tag = ffi::g_source_add_unix_fd(g_source, fd, ffi::G_IO_IN);
+                }
+                FdWatch::PollFd => {
+                    let raw = g_source as *mut TimerGSourceRaw;
+                    (*raw).poll_fd = ffi::GPollFD {
+                        fd: fd,
+                        events: ffi::G_IO_IN as u16,
+                        revents: 0,
+                    };
+                    ffi::g_source_add_poll(g_source, &mut (*raw).poll_fd);
+                }
+            }
+        }
+        self.apply_settings(g_source);
+        let id = source.attach(context);
+        self.source = Some(source);
+        Ok(id)
+    }
+
+    /// Attaches to the thread-default context of the current thread, or to
+    /// the global default context if there is none.
+    pub fn attach_thread_default(&mut self) -> Result<glib::SourceId> {
+        self.attach(Some(&glib::MainContext::ref_thread_default()))
+    }
+
+    /// Removes the source from its context. The timer keeps running and its
+    /// expirations are dispatched once the source is attached again.
+    pub fn detach(&mut self) -> Result<()> {
+        if !self.is_attached() {
+            return Err(Error::NotAttached);
+        }
+        // Kept, so `is_destroyed` reports the detach
+        if let Some(ref source) = self.source {
+            source.destroy();
+        }
+        Ok(())
+    }
+
+    /// False before `attach`, after `detach`, and once GLib destroyed the
+    /// source, e.g. because the callback returned `TimerAction::Remove`.
+    pub fn is_attached(&self) -> bool {
+        match self.source {
+            Some(ref source) => !source.is_destroyed(),
+            None => false,
+        }
+    }
+
+    /// True once the attached source was destroyed, by `detach` or by GLib,
+    /// e.g. because the callback returned `TimerAction::Remove`. It can be
+    /// attached again.
+    pub fn is_destroyed(&self) -> bool {
+        match self.source {
+            Some(ref source) => source.is_destroyed(),
+            None => false,
+        }
+    }
+
+    /// The GSource of the current or last attachment
+    pub fn source<'a>(&'a self) -> Option<&'a glib::Source> {
+        self.source.as_ref()
+    }
+
+    /// Locks the timer. The source is not dispatched until the guard is
+    /// dropped, its expirations stay pending. The callback of this source
+    /// gets the timer as argument instead.
+    pub fn timer<'a>(&'a self) -> TimerGuard<'a> {
+        TimerGuard { guard: self.lock() }
+    }
+
+    /// Same as `timer`
+    pub fn mut_timer<'a>(&'a mut self) -> TimerGuard<'a> {
+        self.timer()
+    }
+
+    /// See `MissedTickPolicy`
+    pub fn set_missed_tick_policy(&mut self, policy: MissedTickPolicy) {
+        self.lock().timer.set_missed_tick_policy(policy);
+    }
+
+    /// Sets the priority of the source, `Priority::DEFAULT` by default.
+    /// Takes effect immediately if it is attached, and for every later
+    /// `attach`.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+        self.apply_to_source();
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sets the name of the source, as shown by debugging and profiling
+    /// tools. Like `set_priority`, it is kept across attachments.
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+        self.apply_to_source();
+    }
+
+    pub fn name<'a>(&'a self) -> Option<&'a str> {
+        self.name.as_ref().map(|name| &name[..])
+    }
+
+    /// If set, GLib may dispatch the source again while its callback runs a
+    /// nested main loop. That dispatch finds the timer locked by the
+    /// callback and skips it, the expirations stay pending until the
+    /// callback returned, so the nested loop keeps waking up for them.
+    /// False by default. Like `set_priority`, it is kept across
+    /// attachments.
+    pub fn set_can_recurse(&mut self, can_recurse: bool) {
+        self.can_recurse = can_recurse;
+        self.apply_to_source();
+    }
+
+    pub fn can_recurse(&self) -> bool {
+        self.can_recurse
+    }
+
+    /// See `FdWatch`. Takes effect with the next `attach`.
+    pub fn set_fd_watch(&mut self, fd_watch: FdWatch) {
+        self.fd_watch = fd_watch;
+    }
+
+    pub fn fd_watch(&self) -> FdWatch {
+        self.fd_watch
+    }
+
+    fn apply_to_source(&self) {
+        if let Some(ref source) = self.source {
+            if !source.is_destroyed() {
+                self.apply_settings(source.to_glib_none().0);
+            }
+        }
+    }
+
+    fn apply_settings(&self, g_source: *mut ffi::GSource) {
+        unsafe {
+            ffi::g_source_set_priority(g_source, self.priority.0);
+            if let Some(ref name) = self.name {
+                ffi::g_source_set_name(g_source, name.to_glib_none().0);
+            }
+            ffi::g_source_set_can_recurse(g_source, self.can_recurse.into_glib());
+        }
+    }
+
+    /// See `PanicPolicy`
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.lock().panic_policy = policy;
+    }
+
+    /// Returns the panic caught with `PanicPolicy::Propagate`, if any.
+    pub fn take_panic(&mut self) -> Option<Box<dyn Any + Send>> {
+        self.lock().panic.take()
+    }
+
+    /// Re-raises the panic caught with `PanicPolicy::Propagate`, if any.
+    pub fn resume_panic(&mut self) {
+        if let Some(payload) = self.take_panic() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, TimerGSourceInner> {
+        lock(&self.inner)
+    }
+}
+
+// Callback panics are caught while the state is locked, so it is only
+// poisoned if the user panics while holding a `TimerGuard`. The timer is
+// still usable then.
+fn lock<'a>(inner: &'a Mutex<TimerGSourceInner>) -> MutexGuard<'a, TimerGSourceInner> {
+    inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Drop for TimerGSource {
+    fn drop(&mut self) {
+        // `source` drops our reference afterwards
+        if let Some(ref source) = self.source {
+            source.destroy();
+        }
+    }
+}
+
+unsafe extern "C" fn finalize_timerfd_g_source(src: *mut ffi::GSource) {
+    let raw = src as *mut TimerGSourceRaw;
+    // Releases the reference of the GSource to the shared state
+    drop(Arc::from_raw((*raw).inner));
+}
+
+unsafe extern "C" fn dispatch_timerfd_g_source_for_realz(user_data: ffi::gpointer)
+                                                         -> ffi::gboolean {
+    let inner = &*(user_data as *const Mutex<TimerGSourceInner>);
+    let mut guard = match inner.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        // Locked by a `TimerGuard`, or by the callback itself if this is a
+        // recursive dispatch. The expirations stay pending until it is
+        // unlocked.
+        Err(TryLockError::WouldBlock) => return glib::ControlFlow::Continue.into_glib(),
+    };
+    let tgs = &mut *guard;
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        // Have to read, so old timer ticks are not messing up epoll. Nothing
+        // is read after the callback, it may have re-armed the timer.
+        match tgs.timer.read_tick() {
+            // Re-armed since it was polled, e.g. through the handle
+            Ok(ref tick) if tick.expirations == 0 => glib::ControlFlow::Continue,
+            Ok(tick) => dispatch_ticks(tgs, tick),
+            Err(Error::ClockSet) => {
+                let action = tgs.callback_object.clock_set(&mut tgs.timer);
+                apply_action(&mut tgs.timer, action)
+            }
+            Err(err) => {
+                glib::g_critical!("timerfd", "Failed to read from timerfd: {}", err);
+                glib::ControlFlow::Break
+            }
+        }
+    }));
+
+    match result {
+        Ok(cont) => cont.into_glib(),
+        Err(payload) => handle_panic(tgs, payload).into_glib(),
+    }
+}
+
+fn handle_panic(tgs: &mut TimerGSourceInner, payload: Box<dyn Any + Send>) -> glib::ControlFlow {
+    match tgs.panic_policy {
+        PanicPolicy::Abort => process::abort(),
+        PanicPolicy::LogAndDestroy => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                *message
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                &message[..]
+            } else {
+                "Box<Any>"
+            };
+            glib::g_critical!("timerfd", "TimerGSource callback panicked: {}", message);
+        }
+        PanicPolicy::Propagate => tgs.panic = Some(payload),
+    }
+    glib::ControlFlow::Break
+}
+
+fn dispatch_ticks(tgs: &mut TimerGSourceInner, tick: TickInfo) -> glib::ControlFlow {
+    match tgs.timer.missed_tick_policy() {
+        MissedTickPolicy::Coalesce => {
+            let action = tgs.callback_object.callback(&mut tgs.timer, &tick);
+            apply_action(&mut tgs.timer, action)
+        }
+        MissedTickPolicy::Skip => {
+            let tick = TickInfo::new(cmp::min(tick.expirations, 1), tick.deadline,
+                                     tick.dispatched);
+            let action = tgs.callback_object.callback(&mut tgs.timer, &tick);
+            apply_action(&mut tgs.timer, action)
+        }
+        MissedTickPolicy::CatchUp(max) => {
+            if tick.expirations <= 1 {
+                let action = tgs.callback_object.callback(&mut tgs.timer, &tick);
+                return apply_action(&mut tgs.timer, action);
+            }
+            let interval = tgs.timer.interval();
+            let generation = tgs.timer.generation();
+            let n = cmp::min(tick.expirations, cmp::max(max, 1));
+            for i in 0..n {
+                let before_latest = cmp::min(n - 1 - i, i64::MAX as u64) as i64;
+                let deadline = tick.deadline.map(|deadline| {
+                    deadline.saturating_sub(interval.saturating_mul(before_latest))
+                });
+                let single = TickInfo::new(1, deadline, tick.dispatched);
+                let action = tgs.callback_object.callback(&mut tgs.timer, &single);
+                // The remaining ticks belong to the old schedule
+                if action != TimerAction::Continue {
+                    return apply_action(&mut tgs.timer, action);
+                }
+                if tgs.timer.generation() != generation {
+                    break;
+                }
+            }
+            glib::ControlFlow::Continue
+        }
+    }
+}
+
+fn apply_action(timer: &mut Timer, action: TimerAction) -> glib::ControlFlow {
+    let result = match action {
+        TimerAction::Continue => Ok(()),
+        TimerAction::Reschedule(new_value) => timer.reschedule(new_value).map(|_| ()),
+        TimerAction::SetInterval(interval) => {
+            itimerspec::try_from((interval, interval))
+                .and_then(|new_value| timer.reschedule(new_value))
+                .map(|_| ())
+        }
+        TimerAction::Oneshot(timeout) => {
+            itimerspec::try_from((timeout, Duration::new(0, 0)))
+                .and_then(|new_value| timer.reschedule(new_value))
+                .map(|_| ())
+        }
+        TimerAction::Pause => match timer.pause() {
+            // Already stopped or paused by the callback itself
+            Err(Error::NotActive) => Ok(()),
+            result => result,
+        },
+        TimerAction::Remove => return glib::ControlFlow::Break,
+    };
+    match result {
+        Ok(()) => glib::ControlFlow::Continue,
+        Err(err) => {
+            glib::g_critical!("timerfd", "Failed to apply {:?} to timer: {}", action, err);
+            glib::ControlFlow::Break
+        }
+    }
+}
+
+unsafe extern "C" fn dispatch_timerfd_g_source(src: *mut ffi::GSource,
+        callback: ffi::GSourceFunc, user_data: ffi::gpointer) -> ffi::gboolean {
+
+    // Only our own invariants are checked here, the callback catches panics
+    // itself
+    let result = panic::catch_unwind(|| {
+        let raw = src as *mut TimerGSourceRaw;
+        assert_eq!((*raw).inner as ffi::gpointer, user_data);
+        callback.expect("How could this happen? This must be set!")(user_data)
+    });
+    result.unwrap_or_else(|_| process::abort())
+}
+
+static mut TIMER_GSOURCE_FUNCS: ffi::GSourceFuncs = ffi::GSourceFuncs {
+    prepare: None,
+    check: None,
+    dispatch: Some(dispatch_timerfd_g_source),
+    finalize: Some(finalize_timerfd_g_source),
+    closure_callback: None,
+    closure_marshal: None
+};
+
+// Only the timerfd wakes the source up, there is no timeout of its own
+unsafe extern "C" fn prepare_timerfd_g_source(_src: *mut ffi::GSource, timeout: *mut i32)
+                                              -> ffi::gboolean {
+    *timeout = -1;
+    ffi::GFALSE
+}
+
+unsafe extern "C" fn check_timerfd_g_source(src: *mut ffi::GSource) -> ffi::gboolean {
+    let raw = src as *mut TimerGSourceRaw;
+    ((*raw).poll_fd.revents & ffi::G_IO_IN as u16 != 0).into_glib()
+}
+
+// For `FdWatch::PollFd`
+static mut TIMER_GSOURCE_POLL_FUNCS: ffi::GSourceFuncs = ffi::GSourceFuncs {
+    prepare: Some(prepare_timerfd_g_source),
+    check: Some(check_timerfd_g_source),
+    dispatch: Some(dispatch_timerfd_g_source),
+    finalize: Some(finalize_timerfd_g_source),
+    closure_callback: None,
+    closure_marshal: None
+};
@@ -0,0 +1,390 @@
+use std::cmp;
+use std::convert::TryFrom;
+use std::ops;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+
+use error::{Error, Result};
+
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+#[repr(C)]
+pub struct timespec {
+    pub tv_sec: libc::time_t,
+    pub tv_nsec: libc::c_long,
+}
+
+impl Copy for timespec {}
+
+impl timespec {
+    pub(crate) fn is_valid(&self) -> bool {
+        // according to `man timerfd_settime`
+        0 <= self.tv_nsec && self.tv_nsec <= NSEC_MAX
+    }
+}
+
+// Invalid values are compared field by field as well, use `Timespec` if
+// that matters.
+impl PartialOrd for timespec {
+    fn partial_cmp(&self, other: &timespec) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for timespec {
+    fn cmp(&self, other: &timespec) -> cmp::Ordering {
+        match self.tv_sec.cmp(&other.tv_sec) {
+            cmp::Ordering::Equal => self.tv_nsec.cmp(&other.tv_nsec),
+            ord => ord,
+        }
+    }
+}
+
+static NSEC_MAX: libc::c_long = 999_999_999;
+static NSEC_PER_SEC: i128 = 1_000_000_000;
+
+/// A `timespec` that is always valid, i.e. `nsec` is in
+/// `0..1_000_000_000`. Negative times have negative `sec`, e.g. -1.5s is
+/// `sec == -2, nsec == 500_000_000`.
+#[derive(Default,Eq,PartialEq,Ord,PartialOrd,Hash,Clone,Debug)]
+pub struct Timespec {
+    sec: libc::time_t,
+    nsec: libc::c_long,
+}
+
+impl Copy for Timespec {}
+
+impl Timespec {
+    pub const MIN: Timespec = Timespec { sec: libc::time_t::MIN, nsec: 0 };
+    pub const MAX: Timespec = Timespec { sec: libc::time_t::MAX, nsec: 999_999_999 };
+
+    /// Fails if `nsec` is not in `0..1_000_000_000`
+    pub fn new(sec: libc::time_t, nsec: libc::c_long) -> Result<Timespec> {
+        if nsec < 0 || nsec > NSEC_MAX {
+            return Err(Error::InvalidTime);
+        }
+        Ok(Timespec { sec: sec, nsec: nsec })
+    }
+
+    /// Carries nanoseconds outside of `0..1_000_000_000` over into the
+    /// seconds. Returns `None` if the seconds overflow.
+    pub fn normalized(sec: libc::time_t, nsec: i64) -> Option<Timespec> {
+        Timespec::from_total_nanos(sec as i128 * NSEC_PER_SEC + nsec as i128)
+    }
+
+    pub fn from_millis(ms: i64) -> Option<Timespec> {
+        Timespec::from_total_nanos(ms as i128 * 1000 * 1000)
+    }
+
+    pub fn from_nanos(ns: i64) -> Timespec {
+        Timespec::from_total_nanos(ns as i128).expect("i64 nanoseconds always fit")
+    }
+
+    pub fn sec(&self) -> libc::time_t {
+        self.sec
+    }
+
+    pub fn nsec(&self) -> libc::c_long {
+        self.nsec
+    }
+
+    fn total_nanos(&self) -> i128 {
+        self.sec as i128 * NSEC_PER_SEC + self.nsec as i128
+    }
+
+    fn from_total_nanos(nanos: i128) -> Option<Timespec> {
+        let sec = nanos.div_euclid(NSEC_PER_SEC);
+        if sec < libc::time_t::MIN as i128 || sec > libc::time_t::MAX as i128 {
+            return None;
+        }
+        Some(Timespec {
+            sec: sec as libc::time_t,
+            nsec: nanos.rem_euclid(NSEC_PER_SEC) as libc::c_long,
+        })
+    }
+
+    pub fn checked_add(self, rhs: Timespec) -> Option<Timespec> {
+        Timespec::from_total_nanos(self.total_nanos() + rhs.total_nanos())
+    }
+
+    pub fn checked_sub(self, rhs: Timespec) -> Option<Timespec> {
+        Timespec::from_total_nanos(self.total_nanos() - rhs.total_nanos())
+    }
+
+    pub fn checked_mul(self, rhs: i64) -> Option<Timespec> {
+        self.total_nanos().checked_mul(rhs as i128).and_then(Timespec::from_total_nanos)
+    }
+
+    pub fn saturating_add(self, rhs: Timespec) -> Timespec {
+        self.checked_add(rhs).unwrap_or(
+            if rhs.sec < 0 { Timespec::MIN } else { Timespec::MAX })
+    }
+
+    pub fn saturating_sub(self, rhs: Timespec) -> Timespec {
+        self.checked_sub(rhs).unwrap_or(
+            if rhs.sec < 0 { Timespec::MAX } else { Timespec::MIN })
+    }
+
+    pub fn saturating_mul(self, rhs: i64) -> Timespec {
+        self.checked_mul(rhs).unwrap_or(
+            if (self.sec < 0) != (rhs < 0) { Timespec::MIN } else { Timespec::MAX })
+    }
+}
+
+impl ops::Add for Timespec {
+    type Output = Timespec;
+
+    fn add(self, rhs: Timespec) -> Timespec {
+        self.checked_add(rhs).expect("overflow when adding timespecs")
+    }
+}
+
+impl ops::AddAssign for Timespec {
+    fn add_assign(&mut self, rhs: Timespec) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::Sub for Timespec {
+    type Output = Timespec;
+
+    fn sub(self, rhs: Timespec) -> Timespec {
+        self.checked_sub(rhs).expect("overflow when subtracting timespecs")
+    }
+}
+
+impl ops::SubAssign for Timespec {
+    fn sub_assign(&mut self, rhs: Timespec) {
+        *self = *self - rhs;
+    }
+}
+
+impl ops::Mul<i64> for Timespec {
+    type Output = Timespec;
+
+    fn mul(self, rhs: i64) -> Timespec {
+        self.checked_mul(rhs).expect("overflow when multiplying timespec")
+    }
+}
+
+impl ops::MulAssign<i64> for Timespec {
+    fn mul_assign(&mut self, rhs: i64) {
+        *self = *self * rhs;
+    }
+}
+
+impl From<Timespec> for timespec {
+    fn from(ts: Timespec) -> timespec {
+        timespec { tv_sec: ts.sec, tv_nsec: ts.nsec }
+    }
+}
+
+impl TryFrom<timespec> for Timespec {
+    type Error = Error;
+
+    /// Fails for invalid `timespec`s, see `Timespec::normalized` to fix them
+    /// up instead.
+    fn try_from(ts: timespec) -> Result<Timespec> {
+        Timespec::new(ts.tv_sec, ts.tv_nsec)
+    }
+}
+
+impl TryFrom<Duration> for Timespec {
+    type Error = Error;
+
+    fn try_from(duration: Duration) -> Result<Timespec> {
+        Timespec::try_from(timespec::try_from(duration)?)
+    }
+}
+
+impl TryFrom<Timespec> for Duration {
+    type Error = Error;
+
+    /// Fails for negative `Timespec`s
+    fn try_from(ts: Timespec) -> Result<Duration> {
+        Duration::try_from(timespec::from(ts))
+    }
+}
+
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+#[repr(C)]
+pub struct itimerspec {
+    pub it_interval: timespec,
+    pub it_value: timespec,
+}
+
+impl Copy for itimerspec {}
+
+impl itimerspec {
+    pub(crate) fn is_valid(&self) -> bool {
+        self.it_value.is_valid() && self.it_value.tv_sec >= 0
+            && self.it_interval.is_valid() && self.it_interval.tv_sec >= 0
+    }
+}
+
+impl TryFrom<Duration> for timespec {
+    type Error = Error;
+
+    fn try_from(duration: Duration) -> Result<timespec> {
+        if duration.as_secs() > libc::time_t::MAX as u64 {
+            return Err(Error::InvalidTime);
+        }
+        Ok(timespec {
+            tv_sec: duration.as_secs() as libc::time_t,
+            tv_nsec: duration.subsec_nanos() as libc::c_long,
+        })
+    }
+}
+
+impl TryFrom<timespec> for Duration {
+    type Error = Error;
+
+    /// Fails for negative and invalid `timespec`s
+    fn try_from(ts: timespec) -> Result<Duration> {
+        if !ts.is_valid() || ts.tv_sec < 0 {
+            return Err(Error::InvalidTime);
+        }
+        Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+}
+
+impl TryFrom<SystemTime> for timespec {
+    type Error = Error;
+
+    /// The result is on the `ClockId::Realtime` clock. Fails for times
+    /// before the epoch.
+    fn try_from(time: SystemTime) -> Result<timespec> {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => timespec::try_from(duration),
+            Err(_) => Err(Error::InvalidTime),
+        }
+    }
+}
+
+impl TryFrom<timespec> for SystemTime {
+    type Error = Error;
+
+    /// `ts` has to be on the `ClockId::Realtime` clock.
+    fn try_from(ts: timespec) -> Result<SystemTime> {
+        UNIX_EPOCH.checked_add(Duration::try_from(ts)?).ok_or(Error::InvalidTime)
+    }
+}
+
+impl TryFrom<(Duration, Duration)> for itimerspec {
+    type Error = Error;
+
+    /// Converts a `(value, interval)` pair.
+    fn try_from((value, interval): (Duration, Duration)) -> Result<itimerspec> {
+        Ok(itimerspec {
+            it_interval: timespec::try_from(interval)?,
+            it_value: timespec::try_from(value)?,
+        })
+    }
+}
+
+impl TryFrom<itimerspec> for (Duration, Duration) {
+    type Error = Error;
+
+    /// Converts to a `(value, interval)` pair.
+    fn try_from(its: itimerspec) -> Result<(Duration, Duration)> {
+        Ok((Duration::try_from(its.it_value)?, Duration::try_from(its.it_interval)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    use super::{timespec, Timespec};
+
+    fn ts(sec: i64, nsec: i64) -> Timespec {
+        Timespec::new(sec as _, nsec as _).unwrap()
+    }
+
+    #[test]
+    fn new_rejects_invalid_nsec() {
+        assert!(Timespec::new(0, -1).is_err());
+        assert!(Timespec::new(0, 1_000_000_000).is_err());
+        assert_eq!(Timespec::new(-1, 999_999_999).unwrap(), ts(-1, 999_999_999));
+    }
+
+    #[test]
+    fn normalized() {
+        assert_eq!(Timespec::normalized(0, -1), Some(ts(-1, 999_999_999)));
+        assert_eq!(Timespec::normalized(1, 1_500_000_000), Some(ts(2, 500_000_000)));
+        assert_eq!(Timespec::normalized(-1, -1_500_000_000), Some(ts(-3, 500_000_000)));
+        assert_eq!(Timespec::normalized(0, i64::MIN), Some(Timespec::from_nanos(i64::MIN)));
+        assert_eq!(Timespec::normalized(i64::MAX as _, 1_000_000_000), None);
+        assert_eq!(Timespec::normalized(i64::MIN as _, -1), None);
+    }
+
+    #[test]
+    fn from_millis_and_nanos() {
+        assert_eq!(Timespec::from_millis(-1500), Some(ts(-2, 500_000_000)));
+        assert_eq!(Timespec::from_nanos(-1), ts(-1, 999_999_999));
+        assert!(Timespec::from_millis(i64::MAX).is_some());
+    }
+
+    #[test]
+    fn checked_add_and_sub() {
+        assert_eq!(ts(1, 600_000_000).checked_add(ts(0, 600_000_000)),
+                   Some(ts(2, 200_000_000)));
+        assert_eq!(ts(1, 0).checked_sub(ts(0, 1)), Some(ts(0, 999_999_999)));
+        assert_eq!(ts(0, 0).checked_sub(ts(-1, 500_000_000)), Some(ts(0, 500_000_000)));
+        assert_eq!(Timespec::MAX.checked_add(ts(0, 1)), None);
+        assert_eq!(Timespec::MIN.checked_sub(ts(0, 1)), None);
+        assert_eq!(Timespec::MIN.checked_add(Timespec::MAX), Some(ts(-1, 999_999_999)));
+    }
+
+    #[test]
+    fn saturating_add_and_sub() {
+        assert_eq!(Timespec::MAX.saturating_add(ts(0, 1)), Timespec::MAX);
+        assert_eq!(Timespec::MIN.saturating_add(ts(-1, 0)), Timespec::MIN);
+        assert_eq!(Timespec::MIN.saturating_sub(ts(0, 1)), Timespec::MIN);
+        // Subtracting a negative time overflows upwards
+        assert_eq!(Timespec::MAX.saturating_sub(ts(-1, 0)), Timespec::MAX);
+        assert_eq!(ts(5, 0).saturating_sub(ts(-1, 500_000_000)), ts(5, 500_000_000));
+    }
+
+    #[test]
+    fn checked_and_saturating_mul() {
+        assert_eq!(ts(1, 500_000_000).checked_mul(3), Some(ts(4, 500_000_000)));
+        assert_eq!(ts(1, 500_000_000).checked_mul(-1), Some(ts(-2, 500_000_000)));
+        assert_eq!(Timespec::MAX.checked_mul(0), Some(ts(0, 0)));
+        assert_eq!(Timespec::MAX.checked_mul(2), None);
+        assert_eq!(Timespec::MIN.checked_mul(-1), None);
+        assert_eq!(Timespec::MAX.checked_mul(i64::MAX), None);
+        assert_eq!(Timespec::MAX.saturating_mul(2), Timespec::MAX);
+        assert_eq!(Timespec::MAX.saturating_mul(-2), Timespec::MIN);
+        assert_eq!(Timespec::MIN.saturating_mul(2), Timespec::MIN);
+        assert_eq!(Timespec::MIN.saturating_mul(-1), Timespec::MAX);
+    }
+
+    #[test]
+    #[should_panic]
+    fn add_panics_on_overflow() {
+        let _ = Timespec::MAX + ts(0, 1);
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(ts(-1, 999_999_999) < ts(0, 0));
+        assert!(ts(0, 1) > ts(0, 0));
+        assert!(Timespec::MIN < ts(-1, 0));
+        assert!(ts(1, 0) < Timespec::MAX);
+        // Invalid `timespec`s are compared field by field and don't panic
+        let invalid = timespec { tv_sec: 0, tv_nsec: -1 };
+        assert!(invalid < timespec { tv_sec: 0, tv_nsec: 0 });
+        assert!(invalid > timespec { tv_sec: -1, tv_nsec: 999_999_999 });
+    }
+
+    #[test]
+    fn duration_conversions() {
+        assert_eq!(Timespec::try_from(Duration::new(1, 5)).unwrap(), ts(1, 5));
+        assert_eq!(Duration::try_from(ts(1, 5)).unwrap(), Duration::new(1, 5));
+        assert!(Duration::try_from(ts(-1, 999_999_999)).is_err());
+        assert!(Timespec::try_from(timespec { tv_sec: 0, tv_nsec: -1 }).is_err());
+        assert!(Timespec::try_from(Duration::new(u64::MAX, 0)).is_err());
+    }
+}
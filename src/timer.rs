@@ -0,0 +1,491 @@
+use std::cmp;
+use std::convert::TryFrom;
+use std::default;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::time::{Duration, Instant, SystemTime};
+
+use error::{Error, Result};
+use time::{itimerspec, timespec, Timespec};
+use timerfd::{ClockId, TimerEvent, TimerFD};
+
+/// State of a `Timer`, see `Timer::state`
+#[derive(Eq,PartialEq,Clone,Debug)]
+pub enum TimerState {
+    /// Not started yet, or stopped
+    Stopped,
+    /// Started and going to expire
+    Running,
+    /// Paused, `resume` continues with the remaining time
+    Paused,
+    /// Started as one-shot timer and already expired
+    Expired,
+}
+
+impl Copy for TimerState {}
+
+/// The actually used timer
+pub struct Timer {
+    timerfd: TimerFD,
+    current: itimerspec,
+    absolute: bool,
+    cancel_on_set: bool,
+    active: bool,
+    // The remaining time and interval of a paused timer
+    paused: Option<itimerspec>,
+    // When the timer expires next, on its clock
+    next_deadline: Option<Timespec>,
+    // The interval the timer is armed with, `reschedule` doesn't change
+    // the configured one in `current`
+    armed_interval: Timespec,
+    // Bumped whenever the timer is armed or disarmed, so a dispatch can tell
+    // that its callback changed the schedule
+    generation: u64,
+    missed_tick_policy: MissedTickPolicy,
+}
+
+/// How a `TimerGSource` handles a periodic timer that expired more than once
+/// since its last dispatch, e.g. because the main loop was blocked
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+pub enum MissedTickPolicy {
+    /// Call the callback once, `TickInfo::expirations` tells how often the
+    /// timer expired. This is the default.
+    #[default]
+    Coalesce,
+    /// Call the callback once per expiration, but at most this often (at
+    /// least once) per dispatch. Older expirations over the limit are
+    /// dropped. Stops early if the callback re-arms, stops or pauses the
+    /// timer, or returns anything but `TimerAction::Continue`.
+    CatchUp(u64),
+    /// Call the callback once, for the latest expiration only. The timer
+    /// stays aligned to its original phase, so the next expiration is the
+    /// next multiple of the interval after the first one.
+    Skip,
+}
+
+impl Copy for MissedTickPolicy {}
+
+/// Describes the expirations consumed by one read of a `Timer`
+#[derive(Clone,Debug)]
+pub struct TickInfo {
+    /// How often the timer expired since the last read. More than one
+    /// means ticks were missed, zero that nothing was pending.
+    pub expirations: u64,
+    /// When the latest of these expirations was scheduled, on the clock of
+    /// the timer. `None` if there were no expirations.
+    pub deadline: Option<Timespec>,
+    /// When the expirations were read, on the clock of the timer
+    pub dispatched: Timespec,
+    /// How much later than `deadline` the expirations were read
+    pub lateness: Duration,
+}
+
+impl Timer {
+    /// Equivalent to `new_with_clock(ClockId::Monotonic)`
+    pub fn new() -> Result<Timer> {
+        Timer::new_with_clock(ClockId::Monotonic)
+    }
+
+    pub fn new_with_clock(clock: ClockId) -> Result<Timer> {
+        Ok(Timer::from_timerfd(TimerFD::new_with_clock(clock)?))
+    }
+
+    /// Takes over a disarmed `TimerFD`, e.g. one created with custom flags
+    /// by `TimerFD::builder`.
+    pub fn from_timerfd(timerfd: TimerFD) -> Timer {
+        Timer {
+            timerfd: timerfd,
+            current: default::Default::default(),
+            absolute: false,
+            cancel_on_set: false,
+            active: false,
+            paused: None,
+            next_deadline: None,
+            armed_interval: default::Default::default(),
+            generation: 0,
+            missed_tick_policy: MissedTickPolicy::Coalesce,
+        }
+    }
+
+    /// Both have to be >= 0. A zero initial_ms leaves the timer disarmed,
+    /// a zero interval_ms makes it a one-shot timer.
+    pub fn set_interval(&mut self, initial_ms: i64, interval_ms: i64) -> Result<()> {
+        let initial = Timespec::from_millis(initial_ms).ok_or(Error::InvalidTime)?;
+        let interval = Timespec::from_millis(interval_ms).ok_or(Error::InvalidTime)?;
+        self.configure(initial.into(), interval.into(), false)
+    }
+
+    /// Like `set_interval`, but in nanoseconds
+    pub fn set_interval_ns(&mut self, initial_ns: i64, interval_ns: i64) -> Result<()> {
+        let initial = Timespec::from_nanos(initial_ns);
+        let interval = Timespec::from_nanos(interval_ns);
+        self.configure(initial.into(), interval.into(), false)
+    }
+
+    /// Equivalent to `set_interval_ns(timeout_ns, 0)`
+    pub fn set_oneshot_ns(&mut self, timeout_ns: i64) -> Result<()> {
+        self.set_interval_ns(timeout_ns, 0)
+    }
+
+    /// Fire first at `deadline`, an absolute point in time on the clock of
+    /// this timer (see `TimerFD::now`), then every `interval_ms`.
+    pub fn set_deadline(&mut self, deadline: timespec, interval_ms: i64) -> Result<()> {
+        let interval = Timespec::from_millis(interval_ms).ok_or(Error::InvalidTime)?;
+        self.configure(deadline, interval.into(), true)
+    }
+
+    /// Like `set_interval`, but with nanosecond precision
+    pub fn set_interval_duration(&mut self, initial: Duration, interval: Duration)
+                                 -> Result<()> {
+        let new_value = itimerspec::try_from((initial, interval))?;
+        self.configure(new_value.it_value, new_value.it_interval, false)
+    }
+
+    /// Equivalent to `set_interval_duration(timeout, Duration::new(0, 0))`
+    pub fn set_oneshot_duration(&mut self, timeout: Duration) -> Result<()> {
+        self.set_interval_duration(timeout, Duration::new(0, 0))
+    }
+
+    /// Like `set_deadline`, only supported for `ClockId::Realtime` and
+    /// `ClockId::RealtimeAlarm`.
+    pub fn set_deadline_system_time(&mut self, deadline: SystemTime, interval: Duration)
+                                    -> Result<()> {
+        match self.timerfd.clock() {
+            ClockId::Realtime | ClockId::RealtimeAlarm => (),
+            _ => return Err(Error::UnsupportedClock),
+        }
+        self.configure(timespec::try_from(deadline)?, timespec::try_from(interval)?, true)
+    }
+
+    /// Like `set_deadline`. As `Instant` can't be converted to the clock of
+    /// the timer directly, this is only exact up to the time between reading
+    /// both clocks.
+    pub fn set_deadline_instant(&mut self, deadline: Instant, interval: Duration)
+                                -> Result<()> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let now = Duration::try_from(self.now()?)?;
+        let deadline = now.checked_add(remaining).ok_or(Error::InvalidTime)?;
+        self.configure(timespec::try_from(deadline)?, timespec::try_from(interval)?, true)
+    }
+
+    fn configure(&mut self, value: timespec, interval: timespec, absolute: bool)
+                 -> Result<()> {
+        let new_value = itimerspec { it_interval: interval, it_value: value };
+        if !new_value.is_valid() {
+            return Err(Error::InvalidTime);
+        }
+        if self.is_running()? {
+            return Err(Error::Active);
+        }
+        self.current = new_value;
+        self.absolute = absolute;
+        Ok(())
+    }
+
+    /// If set, a timer armed with `set_deadline` reports
+    /// `TimerEvent::ClockSet` when its clock is changed discontinuously.
+    /// Only supported for `ClockId::Realtime` and `ClockId::RealtimeAlarm`,
+    /// the kernel ignores it for other clocks and for relative times.
+    pub fn set_cancel_on_set(&mut self, cancel_on_set: bool) -> Result<()> {
+        if self.is_running()? {
+            return Err(Error::Active);
+        }
+        match self.timerfd.clock() {
+            ClockId::Realtime | ClockId::RealtimeAlarm => (),
+            _ if cancel_on_set => return Err(Error::UnsupportedClock),
+            _ => (),
+        }
+        self.cancel_on_set = cancel_on_set;
+        Ok(())
+    }
+
+    /// Returns the current time of the clock this timer is based on.
+    pub fn now(&self) -> Result<timespec> {
+        self.timerfd.now()
+    }
+
+    /// See `TimerFD::read_event`
+    pub fn read_event(&mut self) -> Result<Option<TimerEvent>> {
+        let event = self.timerfd.read_event()?;
+        if let Some(TimerEvent::Expired(expirations)) = event {
+            self.account(expirations);
+        }
+        Ok(event)
+    }
+
+    /// See `TimerFD::read_expirations`
+    pub fn read_expirations(&mut self) -> Result<u64> {
+        let expirations = self.timerfd.read_expirations()?;
+        self.account(expirations);
+        Ok(expirations)
+    }
+
+    /// See `TimerFD::read_expirations_blocking`
+    pub fn read_expirations_blocking(&mut self) -> Result<u64> {
+        let expirations = self.timerfd.read_expirations_blocking()?;
+        self.account(expirations);
+        Ok(expirations)
+    }
+
+    /// Like `read_expirations`, but also reports when the expirations were
+    /// scheduled and how late they are read.
+    pub fn read_tick(&mut self) -> Result<TickInfo> {
+        let expirations = self.timerfd.read_expirations()?;
+        let deadline = self.account(expirations);
+        let dispatched = Timespec::try_from(self.now()?)?;
+        Ok(TickInfo::new(expirations, deadline, dispatched))
+    }
+
+    /// See `MissedTickPolicy`
+    pub fn set_missed_tick_policy(&mut self, policy: MissedTickPolicy) {
+        self.missed_tick_policy = policy;
+    }
+
+    pub fn missed_tick_policy(&self) -> MissedTickPolicy {
+        self.missed_tick_policy
+    }
+
+    pub(crate) fn interval(&self) -> Timespec {
+        self.armed_interval
+    }
+
+    #[cfg(feature = "glib")]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Advances `next_deadline` past `expirations` and returns the deadline
+    // of the last of them.
+    fn account(&mut self, expirations: u64) -> Option<Timespec> {
+        let next_deadline = match self.next_deadline {
+            Some(next_deadline) if expirations > 0 => next_deadline,
+            _ => return None,
+        };
+        let interval = self.interval();
+        if interval == default::Default::default() {
+            self.next_deadline = None;
+            return Some(next_deadline);
+        }
+        let missed = cmp::min(expirations - 1, i64::MAX as u64) as i64;
+        let deadline = next_deadline.saturating_add(interval.saturating_mul(missed));
+        self.next_deadline = Some(deadline.saturating_add(interval));
+        Some(deadline)
+    }
+
+    /// See `TimerFD::set_ticks`
+    #[cfg(feature = "set-ticks")]
+    pub fn set_ticks(&mut self, ticks: u64) -> Result<()> {
+        self.timerfd.set_ticks(ticks)
+    }
+
+    /// Equivalent to `set_interval(timeout_ms, 0)`
+    pub fn set_oneshot(&mut self, timeout_ms: i64) -> Result<()> {
+        self.set_interval(timeout_ms, 0)
+    }
+
+    /// Starts the timer with the configured time. A paused timer starts
+    /// over as well, use `resume` to continue it instead, and so does an
+    /// expired one-shot timer. If the configured time is zero, the timer
+    /// stays disarmed.
+    pub fn start(&mut self) -> Result<()> {
+        if self.is_running()? {
+            return Err(Error::Active);
+        }
+        self.arm()?;
+        Ok(())
+    }
+
+    /// Arms the timer with the configured time again, whether it is active,
+    /// paused or not. Returns the previous setting, see `reschedule`.
+    pub fn restart(&mut self) -> Result<itimerspec> {
+        self.arm()
+    }
+
+    /// Arms the timer with `new_value`, relative to now, whether it is
+    /// active, paused or not. A zero `it_value` disarms it. This takes a
+    /// single `timerfd_settime`, so no expiration can get lost in between.
+    /// Returns the previous setting, with `it_value` relative to now and
+    /// zero if the timer was not armed.
+    ///
+    /// The configured time is kept, `restart` or `stop` followed by `start`
+    /// return to it.
+    pub fn reschedule(&mut self, new_value: itimerspec) -> Result<itimerspec> {
+        if !new_value.is_valid() {
+            return Err(Error::InvalidTime);
+        }
+        self.arm_with(new_value, false)
+    }
+
+    fn arm(&mut self) -> Result<itimerspec> {
+        let (new_value, absolute) = (self.current, self.absolute);
+        self.arm_with(new_value, absolute)
+    }
+
+    fn arm_with(&mut self, new_value: itimerspec, absolute: bool) -> Result<itimerspec> {
+        let value = Timespec::try_from(new_value.it_value)?;
+        let interval = Timespec::try_from(new_value.it_interval)?;
+        let next_deadline = if value == default::Default::default() {
+            None
+        } else if absolute {
+            Some(value)
+        } else {
+            Timespec::try_from(self.now()?)?.checked_add(value)
+        };
+        let old_value = if absolute && self.cancel_on_set {
+            self.timerfd.settime_absolute_cancel_on_set(&new_value)?
+        } else if absolute {
+            self.timerfd.settime_absolute(&new_value)?
+        } else {
+            self.timerfd.settime(&new_value)?
+        };
+        // Like the kernel, treat a zero value as disarmed
+        self.active = new_value.it_value != default::Default::default();
+        self.paused = None;
+        self.next_deadline = next_deadline;
+        self.armed_interval = interval;
+        self.generation = self.generation.wrapping_add(1);
+        Ok(old_value)
+    }
+
+    /// Disarms an active or paused timer. The next `start` uses the
+    /// configured time again.
+    pub fn stop(&mut self) -> Result<()> {
+        if !self.active && self.paused.is_none() {
+            return Err(Error::NotActive);
+        }
+        if self.active {
+            let zero = default::Default::default();
+            self.timerfd.settime(&zero)?;
+        }
+        self.active = false;
+        self.paused = None;
+        self.next_deadline = None;
+        self.generation = self.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Disarms a running timer, keeping its remaining time and interval
+    /// for `resume`. Fails with `Error::NotActive` for an expired one-shot
+    /// timer, as there is nothing left to resume.
+    pub fn pause(&mut self) -> Result<()> {
+        if !self.is_running()? {
+            return Err(Error::NotActive);
+        }
+        let zero = default::Default::default();
+        // The kernel always reports the remaining time relative to now
+        let remaining = self.timerfd.settime(&zero)?;
+        self.active = false;
+        self.paused = Some(remaining);
+        self.next_deadline = None;
+        self.generation = self.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Continues a paused timer with the time that was remaining when it
+    /// was paused.
+    pub fn resume(&mut self) -> Result<()> {
+        let remaining = match self.paused {
+            Some(remaining) => remaining,
+            None => return Err(Error::NotPaused),
+        };
+        let now = Timespec::try_from(self.now()?)?;
+        self.timerfd.settime(&remaining)?;
+        self.active = remaining.it_value != default::Default::default();
+        self.paused = None;
+        self.next_deadline = if self.active {
+            now.checked_add(Timespec::try_from(remaining.it_value)?)
+        } else {
+            None
+        };
+        self.generation = self.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    // Active and not an expired one-shot timer
+    fn is_running(&self) -> Result<bool> {
+        Ok(self.state()? == TimerState::Running)
+    }
+
+    pub fn state(&self) -> Result<TimerState> {
+        if self.paused.is_some() {
+            return Ok(TimerState::Paused);
+        }
+        if !self.active {
+            return Ok(TimerState::Stopped);
+        }
+        if self.timerfd.gettime()?.it_value == default::Default::default() {
+            Ok(TimerState::Expired)
+        } else {
+            Ok(TimerState::Running)
+        }
+    }
+
+    /// Time until the next expiration of a running timer, or the time that
+    /// was remaining when it was paused. Zero otherwise.
+    pub fn remaining(&self) -> Result<Duration> {
+        let remaining = match self.paused {
+            Some(remaining) => remaining.it_value,
+            None if self.active => self.timerfd.gettime()?.it_value,
+            None => default::Default::default(),
+        };
+        Duration::try_from(remaining)
+    }
+}
+
+impl TickInfo {
+    pub(crate) fn new(expirations: u64, deadline: Option<Timespec>, dispatched: Timespec) -> TickInfo {
+        let lateness = deadline
+            .and_then(|deadline| dispatched.checked_sub(deadline))
+            .and_then(|lateness| Duration::try_from(lateness).ok())
+            .unwrap_or(Duration::new(0, 0));
+        TickInfo {
+            expirations: expirations,
+            deadline: deadline,
+            dispatched: dispatched,
+            lateness: lateness,
+        }
+    }
+}
+
+impl AsRawFd for Timer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timerfd.as_raw_fd()
+    }
+}
+
+impl AsFd for Timer {
+    fn as_fd<'a>(&'a self) -> BorrowedFd<'a> {
+        self.timerfd.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+
+    use super::{Timer, TimerState};
+
+    #[test]
+    fn pause_rejects_expired_oneshot() {
+        let mut timer = Timer::new().unwrap();
+        timer.set_oneshot(1).unwrap();
+        timer.start().unwrap();
+        timer.read_expirations_blocking().unwrap();
+
+        match timer.pause() {
+            Err(Error::NotActive) => (),
+            result => panic!("Expected Error::NotActive, got {:?}", result),
+        }
+        assert_eq!(timer.state().unwrap(), TimerState::Expired);
+    }
+
+    #[test]
+    fn pause_rejects_stopped_and_paused() {
+        let mut timer = Timer::new().unwrap();
+        timer.set_oneshot(60000).unwrap();
+        assert!(timer.pause().is_err());
+        timer.start().unwrap();
+        timer.pause().unwrap();
+        assert!(timer.pause().is_err());
+        assert_eq!(timer.state().unwrap(), TimerState::Paused);
+    }
+}
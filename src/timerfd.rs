@@ -0,0 +1,413 @@
+use std::default;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+
+use libc;
+
+use error::{Error, Result};
+use time::{itimerspec, timespec};
+
+extern "C" {
+    fn timerfd_create(clockid: libc::c_int, flags: libc::c_int) -> libc::c_int;
+    fn timerfd_settime(fd: libc::c_int, flags: libc::c_int,
+                       new_value: *const itimerspec, old_value: *mut itimerspec) -> libc::c_int;
+    fn timerfd_gettime(fd: libc::c_int, curr_value: *mut itimerspec) -> libc::c_int;
+}
+
+static CLOCK_REALTIME: libc::c_int = 0;
+static CLOCK_MONOTONIC: libc::c_int = 1;
+static CLOCK_BOOTTIME: libc::c_int = 7;
+static CLOCK_REALTIME_ALARM: libc::c_int = 8;
+static CLOCK_BOOTTIME_ALARM: libc::c_int = 9;
+static TFD_CLOEXEC: libc::c_int = 0o2000000;
+static TFD_NONBLOCK: libc::c_int = 0o0004000;
+static TFD_TIMER_ABSTIME: libc::c_int = 1;
+static TFD_TIMER_CANCEL_ON_SET: libc::c_int = 2;
+// _IOW('T', 0, u64)
+#[cfg(feature = "set-ticks")]
+static TFD_IOC_SET_TICKS: libc::c_ulong = 0x40085400;
+
+/// The clock a timer is based on, see `man timerfd_create`
+#[derive(Default,Eq,PartialEq,Clone,Debug)]
+pub enum ClockId {
+    /// Settable system-wide wall clock
+    Realtime,
+    /// Nonsettable clock that is not affected by changes to the wall clock
+    #[default]
+    Monotonic,
+    /// Like `Monotonic`, but also counts the time the system was suspended
+    Boottime,
+    /// Like `Realtime`, but wakes up the system if it is suspended
+    RealtimeAlarm,
+    /// Like `Boottime`, but wakes up the system if it is suspended
+    BoottimeAlarm,
+}
+
+impl Copy for ClockId {}
+
+impl ClockId {
+    fn as_raw(&self) -> libc::c_int {
+        match *self {
+            ClockId::Realtime => CLOCK_REALTIME,
+            ClockId::Monotonic => CLOCK_MONOTONIC,
+            ClockId::Boottime => CLOCK_BOOTTIME,
+            ClockId::RealtimeAlarm => CLOCK_REALTIME_ALARM,
+            ClockId::BoottimeAlarm => CLOCK_BOOTTIME_ALARM,
+        }
+    }
+
+    fn from_raw(clock: libc::c_int) -> Option<ClockId> {
+        match clock {
+            c if c == CLOCK_REALTIME => Some(ClockId::Realtime),
+            c if c == CLOCK_MONOTONIC => Some(ClockId::Monotonic),
+            c if c == CLOCK_BOOTTIME => Some(ClockId::Boottime),
+            c if c == CLOCK_REALTIME_ALARM => Some(ClockId::RealtimeAlarm),
+            c if c == CLOCK_BOOTTIME_ALARM => Some(ClockId::BoottimeAlarm),
+            _ => None,
+        }
+    }
+
+    /// Looks up the clock of a timerfd in `/proc/self/fdinfo`.
+    fn of_fd(fd: RawFd) -> Option<ClockId> {
+        let info = match fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)) {
+            Ok(info) => info,
+            Err(_) => return None,
+        };
+        info.lines()
+            .filter_map(|line| line.strip_prefix("clockid:"))
+            .filter_map(|clock| clock.trim().parse().ok())
+            .filter_map(ClockId::from_raw)
+            .next()
+    }
+}
+
+/// What a read from a timerfd reported
+#[derive(Eq,PartialEq,Clone,Debug)]
+pub enum TimerEvent {
+    /// The timer expired this many times since the last read
+    Expired(u64),
+    /// The clock of a timer armed with `TFD_TIMER_CANCEL_ON_SET` was changed
+    /// discontinuously, e.g. by NTP or `date -s`. The timer stays armed.
+    ClockSet,
+}
+
+impl Copy for TimerEvent {}
+
+/// Configures the flags a `TimerFD` is created with. By default the timer
+/// uses `ClockId::Monotonic` and is non-blocking and close-on-exec.
+#[derive(Clone,Debug)]
+pub struct TimerFDBuilder {
+    clock: ClockId,
+    nonblocking: bool,
+    cloexec: bool,
+}
+
+impl Copy for TimerFDBuilder {}
+
+impl default::Default for TimerFDBuilder {
+    fn default() -> TimerFDBuilder {
+        TimerFDBuilder {
+            clock: ClockId::Monotonic,
+            nonblocking: true,
+            cloexec: true,
+        }
+    }
+}
+
+impl TimerFDBuilder {
+    pub fn new() -> TimerFDBuilder {
+        default::Default::default()
+    }
+
+    pub fn clock(&mut self, clock: ClockId) -> &mut TimerFDBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether `O_NONBLOCK` is set. Only matters for reads from the raw fd,
+    /// `read_event` and `read_expirations` never wait either way.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut TimerFDBuilder {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Whether the fd is closed in children created with `exec`
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut TimerFDBuilder {
+        self.cloexec = cloexec;
+        self
+    }
+
+    pub fn build(&self) -> Result<TimerFD> {
+        let mut flags = 0;
+        if self.nonblocking {
+            flags |= TFD_NONBLOCK;
+        }
+        if self.cloexec {
+            flags |= TFD_CLOEXEC;
+        }
+        unsafe {
+            let fd = timerfd_create(self.clock.as_raw(), flags);
+            if fd == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(TimerFD { fd: fd, clock: self.clock, nonblocking: self.nonblocking })
+        }
+    }
+}
+
+/// Slightly nicer interface to the C functions.
+pub struct TimerFD {
+    fd: libc::c_int,
+    clock: ClockId,
+    // Whether `O_NONBLOCK` is set, reads poll first otherwise
+    nonblocking: bool,
+}
+
+impl TimerFD {
+    /// Equivalent to `new_with_clock(ClockId::Monotonic)`
+    pub fn new() -> Result<TimerFD> {
+        TimerFD::new_with_clock(ClockId::Monotonic)
+    }
+
+    /// Creates a non-blocking, close-on-exec timer, see `builder` for
+    /// other flags.
+    pub fn new_with_clock(clock: ClockId) -> Result<TimerFD> {
+        TimerFD::builder().clock(clock).build()
+    }
+
+    pub fn builder() -> TimerFDBuilder {
+        TimerFDBuilder::new()
+    }
+
+    /// Changes `O_NONBLOCK` of the file descriptor.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        unsafe {
+            let flags = libc::fcntl(self.fd, libc::F_GETFL);
+            if flags == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            let flags = if nonblocking {
+                flags | libc::O_NONBLOCK
+            } else {
+                flags & !libc::O_NONBLOCK
+            };
+            if libc::fcntl(self.fd, libc::F_SETFL, flags) == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            self.nonblocking = nonblocking;
+            Ok(())
+        }
+    }
+
+    /// Changes `FD_CLOEXEC` of the file descriptor.
+    pub fn set_cloexec(&mut self, cloexec: bool) -> Result<()> {
+        unsafe {
+            let flags = libc::fcntl(self.fd, libc::F_GETFD);
+            if flags == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            let flags = if cloexec {
+                flags | libc::FD_CLOEXEC
+            } else {
+                flags & !libc::FD_CLOEXEC
+            };
+            if libc::fcntl(self.fd, libc::F_SETFD, flags) == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(())
+        }
+    }
+
+    pub fn clock(&self) -> ClockId {
+        self.clock
+    }
+
+    /// Returns the current time of the clock this timer is based on.
+    pub fn now(&self) -> Result<timespec> {
+        unsafe {
+            let mut result = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+            let ret = libc::clock_gettime(self.clock.as_raw() as libc::clockid_t, &mut result);
+            if ret != 0 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(timespec { tv_sec: result.tv_sec, tv_nsec: result.tv_nsec })
+        }
+    }
+
+    /// `new_value.it_value` is relative to the current time of the clock.
+    pub fn settime(&mut self, new_value: &itimerspec) -> Result<itimerspec> {
+        self.settime_with_flags(0, new_value)
+    }
+
+    /// `new_value.it_value` is an absolute point in time on the clock of
+    /// this timer, as returned by `now`.
+    pub fn settime_absolute(&mut self, new_value: &itimerspec) -> Result<itimerspec> {
+        self.settime_with_flags(TFD_TIMER_ABSTIME, new_value)
+    }
+
+    /// Like `settime_absolute`, but `read_event` reports
+    /// `TimerEvent::ClockSet` if the clock is changed discontinuously. Only
+    /// supported for `ClockId::Realtime` and `ClockId::RealtimeAlarm`.
+    pub fn settime_absolute_cancel_on_set(&mut self, new_value: &itimerspec)
+                                          -> Result<itimerspec> {
+        self.settime_with_flags(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET, new_value)
+    }
+
+    fn settime_with_flags(&mut self, flags: libc::c_int, new_value: &itimerspec)
+                          -> Result<itimerspec> {
+        unsafe {
+            let mut result = default::Default::default();
+            let ret = timerfd_settime(self.fd, flags, new_value, &mut result);
+            if ret != 0 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(result)
+        }
+    }
+
+    pub fn gettime(&self) -> Result<itimerspec> {
+        unsafe {
+            let mut result = default::Default::default();
+            let ret = timerfd_gettime(self.fd, &mut result);
+            if ret != 0 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(result)
+        }
+    }
+
+    /// Returns `None` if nothing happened since the last read. Never waits,
+    /// even if the file descriptor is blocking.
+    pub fn read_event(&mut self) -> Result<Option<TimerEvent>> {
+        if !self.nonblocking && !self.poll_readable(0)? {
+            return Ok(None);
+        }
+        let mut expirations = 0u64;
+        let n = unsafe {
+            libc::read(
+                self.fd,
+                (&mut expirations as *mut u64) as *mut libc::c_void,
+                8)
+        };
+        if n == 8 {
+            return Ok(Some(TimerEvent::Expired(expirations)));
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EAGAIN) => Ok(None),
+            Some(libc::ECANCELED) => Ok(Some(TimerEvent::ClockSet)),
+            _ => Err(Error::Io(err)),
+        }
+    }
+
+    /// Returns how often the timer expired since the last read, or 0 if it
+    /// didn't. Never waits, see `read_expirations_blocking` for that.
+    pub fn read_expirations(&mut self) -> Result<u64> {
+        match self.read_event()? {
+            Some(TimerEvent::Expired(n)) => Ok(n),
+            Some(TimerEvent::ClockSet) => Err(Error::ClockSet),
+            None => Ok(0),
+        }
+    }
+
+    /// Like `read_expirations`, but waits until the timer expired at least
+    /// once. Blocks forever if the timer is not armed.
+    pub fn read_expirations_blocking(&mut self) -> Result<u64> {
+        loop {
+            let n = self.read_expirations()?;
+            if n != 0 {
+                return Ok(n);
+            }
+            self.poll_readable(-1)?;
+        }
+    }
+
+    /// Makes the timer report `ticks` (> 0) expirations, as if it had
+    /// expired that often. Meant for tests, needs a kernel built with
+    /// `CONFIG_CHECKPOINT_RESTORE`.
+    #[cfg(feature = "set-ticks")]
+    pub fn set_ticks(&mut self, ticks: u64) -> Result<()> {
+        let ret = unsafe { libc::ioctl(self.fd, TFD_IOC_SET_TICKS as _, &ticks as *const u64) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOTTY) {
+                return Err(Error::UnsupportedByKernel);
+            }
+            return Err(Error::Io(err));
+        }
+        Ok(())
+    }
+
+    // Waits up to `timeout_ms` for the fd to become readable, forever if
+    // negative. Returns whether it is.
+    fn poll_readable(&self, timeout_ms: libc::c_int) -> Result<bool> {
+        let mut pollfd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        let ret = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(Error::Io(err));
+            }
+            return Ok(false);
+        }
+        Ok(pollfd.revents & libc::POLLIN != 0)
+    }
+}
+
+impl Drop for TimerFD {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl AsRawFd for TimerFD {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for TimerFD {
+    fn as_fd<'a>(&'a self) -> BorrowedFd<'a> {
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl IntoRawFd for TimerFD {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        // The caller owns the fd now, so `drop` must not close it
+        mem::forget(self);
+        fd
+    }
+}
+
+impl FromRawFd for TimerFD {
+    /// `fd` has to be an owned timerfd. Its clock is looked up in
+    /// `/proc/self/fdinfo` and assumed to be `ClockId::Monotonic` if that
+    /// fails.
+    unsafe fn from_raw_fd(fd: RawFd) -> TimerFD {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        TimerFD {
+            fd: fd,
+            clock: ClockId::of_fd(fd).unwrap_or(ClockId::Monotonic),
+            nonblocking: flags != -1 && flags & libc::O_NONBLOCK != 0,
+        }
+    }
+}
+
+impl From<OwnedFd> for TimerFD {
+    /// See `from_raw_fd`
+    fn from(fd: OwnedFd) -> TimerFD {
+        unsafe { TimerFD::from_raw_fd(fd.into_raw_fd()) }
+    }
+}
+
+impl From<TimerFD> for OwnedFd {
+    fn from(timerfd: TimerFD) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(timerfd.into_raw_fd()) }
+    }
+}
@@ -0,0 +1,61 @@
+// Attaching a `TimerGSource` created with `from_local_fn`
+#![cfg(feature = "glib")]
+
+extern crate glib;
+extern crate timerfd;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+
+use timerfd::{Error, TimerAction, TimerGSource};
+
+fn local_source() -> TimerGSource {
+    let calls = Rc::new(Cell::new(0));
+    TimerGSource::from_local_fn(move |_timer| {
+        calls.set(calls.get() + 1);
+        TimerAction::Continue
+    }).unwrap()
+}
+
+#[test]
+fn attach_without_pushed_context_fails() {
+    thread::spawn(|| {
+        let mut source = local_source();
+        // Both mean the global default context, which another thread may
+        // iterate
+        match source.attach(None) {
+            Err(Error::ForeignContext) => (),
+            result => panic!("attach(None) returned {:?}", result),
+        }
+        match source.attach_thread_default() {
+            Err(Error::ForeignContext) => (),
+            result => panic!("attach_thread_default returned {:?}", result),
+        }
+    }).join().unwrap();
+}
+
+#[test]
+fn attach_to_foreign_context_fails() {
+    let context = glib::MainContext::new();
+    let mut source = local_source();
+    match source.attach(Some(&context)) {
+        Err(Error::ForeignContext) => (),
+        result => panic!("attach returned {:?}", result),
+    }
+}
+
+#[test]
+fn attach_to_pushed_or_owned_context() {
+    thread::spawn(|| {
+        let context = glib::MainContext::new();
+        let mut source = local_source();
+        context.with_thread_default(|| {
+            source.attach_thread_default().unwrap();
+        }).unwrap();
+        source.detach().unwrap();
+
+        let _guard = context.acquire().unwrap();
+        source.attach(Some(&context)).unwrap();
+    }).join().unwrap();
+}
@@ -0,0 +1,287 @@
+// Changing the timer from inside the callback of a `TimerGSource`
+#![cfg(feature = "glib")]
+
+extern crate glib;
+extern crate timerfd;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use timerfd::{FdWatch, TimerAction, TimerGSource};
+
+// Iterates `context` until `done` or a second passed
+fn iterate_until<F: Fn() -> bool>(context: &glib::MainContext, done: F) {
+    let start = Instant::now();
+    while !done() && start.elapsed() < Duration::from_secs(1) {
+        context.iteration(false);
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// Iterates `context` for `duration`
+fn iterate_for(context: &glib::MainContext, duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        context.iteration(false);
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn stop_and_start_inside_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            timer.stop().unwrap();
+            timer.start().unwrap();
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.mut_timer().set_oneshot(5).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    // The tick of the restarted timer is not swallowed
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // And it expires only once
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn start_expired_oneshot_inside_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            timer.start().unwrap();
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.mut_timer().set_oneshot(5).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn restart_inside_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+            timer.restart().unwrap();
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.mut_timer().set_oneshot(5).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 3);
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn stop_inside_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        timer.stop().unwrap();
+        TimerAction::Continue
+    }).unwrap();
+    source.mut_timer().set_interval(1, 1).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    iterate_until(&context, || calls.load(Ordering::SeqCst) > 0);
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(source.is_attached());
+}
+
+#[test]
+fn change_interval_inside_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            timer.stop().unwrap();
+            timer.set_interval(5, 60000).unwrap();
+            timer.start().unwrap();
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.mut_timer().set_interval(1, 1).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    // One tick of the old schedule, then exactly one of the new one
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 2);
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn action_rearms_after_callback() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |_timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            TimerAction::Oneshot(Duration::from_millis(5))
+        } else {
+            TimerAction::Continue
+        }
+    }).unwrap();
+    source.mut_timer().set_interval(1, 1).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 2);
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn recursive_dispatch_is_skipped() {
+    let context = glib::MainContext::new();
+    let nested = context.clone();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let nested_calls = Arc::new(AtomicUsize::new(0));
+    let nested_counter = nested_calls.clone();
+    let mut source = TimerGSource::from_fn(move |_timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            // The timer expires again meanwhile, but the nested loop must
+            // not dispatch it
+            thread::sleep(Duration::from_millis(10));
+            for _ in 0..5 {
+                nested.iteration(false);
+            }
+            nested_counter.store(counter.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.set_can_recurse(true);
+    source.mut_timer().set_interval(1, 1).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    // The pending expirations are dispatched once the callback returned
+    iterate_until(&context, || calls.load(Ordering::SeqCst) >= 2);
+    assert_eq!(nested_calls.load(Ordering::SeqCst), 1);
+    assert!(calls.load(Ordering::SeqCst) >= 2);
+}
+
+#[test]
+fn restart_inside_callback_with_poll_fd() {
+    let context = glib::MainContext::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut source = TimerGSource::from_fn(move |timer| {
+        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            timer.restart().unwrap();
+        }
+        TimerAction::Continue
+    }).unwrap();
+    source.set_fd_watch(FdWatch::PollFd);
+    source.mut_timer().set_oneshot(5).unwrap();
+    source.mut_timer().start().unwrap();
+    source.attach(Some(&context)).unwrap();
+
+    iterate_until(&context, || calls.load(Ordering::SeqCst) == 2);
+    iterate_for(&context, Duration::from_millis(20));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+// Inject pending expirations with `set_ticks` instead of waiting for them.
+// Ignored by default, run with `cargo test --features glib,set-ticks -- --ignored`
+#[cfg(feature = "set-ticks")]
+mod set_ticks {
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use glib;
+    use timerfd::{itimerspec, MissedTickPolicy, TimerAction, TimerGSource};
+
+    #[test]
+    #[ignore = "needs TFD_IOC_SET_TICKS, i.e. a kernel with CONFIG_CHECKPOINT_RESTORE"]
+    fn catch_up_stops_after_rearm() {
+        let context = glib::MainContext::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut source = TimerGSource::from_fn(move |timer| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                let new_value = (Duration::from_secs(60), Duration::from_secs(60));
+                timer.reschedule(itimerspec::try_from(new_value).unwrap()).unwrap();
+            }
+            TimerAction::Continue
+        }).unwrap();
+        source.set_missed_tick_policy(MissedTickPolicy::CatchUp(100));
+        source.mut_timer().set_interval(60000, 60000).unwrap();
+        source.mut_timer().start().unwrap();
+        source.attach(Some(&context)).unwrap();
+
+        // Ten ticks are pending, but those after the re-arm are dropped
+        source.mut_timer().set_ticks(10).unwrap();
+        while context.iteration(false) {}
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[ignore = "needs TFD_IOC_SET_TICKS, i.e. a kernel with CONFIG_CHECKPOINT_RESTORE"]
+    fn catch_up_without_rearm() {
+        let context = glib::MainContext::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut source = TimerGSource::from_fn(move |_timer| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            TimerAction::Continue
+        }).unwrap();
+        source.set_missed_tick_policy(MissedTickPolicy::CatchUp(100));
+        source.mut_timer().set_interval(60000, 60000).unwrap();
+        source.mut_timer().start().unwrap();
+        source.attach(Some(&context)).unwrap();
+
+        source.mut_timer().set_ticks(10).unwrap();
+        while context.iteration(false) {}
+        assert_eq!(calls.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    #[ignore = "needs TFD_IOC_SET_TICKS, i.e. a kernel with CONFIG_CHECKPOINT_RESTORE"]
+    fn rearm_from_handle_drops_pending_ticks() {
+        let context = glib::MainContext::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut source = TimerGSource::from_fn(move |_timer| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            TimerAction::Continue
+        }).unwrap();
+        source.mut_timer().set_interval(60000, 60000).unwrap();
+        source.mut_timer().start().unwrap();
+        source.attach(Some(&context)).unwrap();
+
+        source.mut_timer().set_ticks(3).unwrap();
+        source.mut_timer().restart().unwrap();
+        while context.iteration(false) {}
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}